@@ -0,0 +1,119 @@
+use std::path::Path;
+use std::time::SystemTime;
+
+use err_context::AnyError;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sled::{Db, Tree};
+use uuid::Uuid;
+
+/// What the cache remembers about a file the last time it was uploaded: its size
+/// and mtime (to cheaply detect a change without re-reading the file) plus the
+/// hash it was last uploaded under.
+#[derive(Serialize, Deserialize, Debug)]
+struct CacheEntry {
+    size: u64,
+    mtime_ms: u128,
+    hash: String,
+}
+
+/// The state of a chunked upload that hasn't been finalized yet: which upload it
+/// is (so a retry talks to the same server-side in-progress upload) and which
+/// chunk digests have already been transferred, so a retry only has to send the
+/// ones that haven't.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendingUpload {
+    pub upload_id: Uuid,
+    pub committed: Vec<String>,
+}
+
+/// A persistent, path-keyed cache backed by `sled` trees under `~/.rbackup/`.
+/// Tracks two things: the last-uploaded size/mtime/hash of a file (so
+/// `upload_files` can skip the network round-trip for unchanged files) and, for
+/// chunked uploads in progress, which chunks have already been committed (so an
+/// interrupted upload resumes instead of restarting from zero).
+pub struct FileCache {
+    db: Db,
+    uploads: Tree,
+}
+
+impl FileCache {
+    pub fn open() -> Result<Self, AnyError> {
+        let dir = dirs::home_dir()
+            .map(|p| p.join(".rbackup/cache.sled"))
+            .ok_or_else(|| AnyError::from("Could not get home dir!"))?;
+
+        debug!("Opening file cache at {:?}", dir);
+
+        let db = sled::open(dir)?;
+        let uploads = db.open_tree("pending_uploads")?;
+
+        Ok(FileCache { db, uploads })
+    }
+
+    /// `true` if `path`'s current size and mtime match what was last recorded.
+    pub fn is_unchanged(&self, path: &Path) -> Result<bool, AnyError> {
+        let metadata = std::fs::metadata(path)?;
+        let mtime_ms = metadata
+            .modified()?
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_millis();
+
+        Ok(match self.get(path)? {
+            Some(entry) => entry.size == metadata.len() && entry.mtime_ms == mtime_ms,
+            None => false,
+        })
+    }
+
+    pub fn record(&self, path: &Path, hash: String) -> Result<(), AnyError> {
+        let metadata = std::fs::metadata(path)?;
+        let mtime_ms = metadata
+            .modified()?
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_millis();
+
+        let entry = CacheEntry {
+            size: metadata.len(),
+            mtime_ms,
+            hash,
+        };
+
+        self.db.insert(Self::key(path), serde_json::to_vec(&entry)?)?;
+        self.db.flush()?;
+
+        Ok(())
+    }
+
+    fn get(&self, path: &Path) -> Result<Option<CacheEntry>, AnyError> {
+        match self.db.get(Self::key(path))? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn pending_upload(&self, path: &Path) -> Result<Option<PendingUpload>, AnyError> {
+        match self.uploads.get(Self::key(path))? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn save_pending_upload(&self, path: &Path, pending: &PendingUpload) -> Result<(), AnyError> {
+        self.uploads
+            .insert(Self::key(path), serde_json::to_vec(pending)?)?;
+        self.uploads.flush()?;
+
+        Ok(())
+    }
+
+    pub fn clear_pending_upload(&self, path: &Path) -> Result<(), AnyError> {
+        self.uploads.remove(Self::key(path))?;
+        self.uploads.flush()?;
+
+        Ok(())
+    }
+
+    fn key(path: &Path) -> Vec<u8> {
+        path.to_string_lossy().as_bytes().to_vec()
+    }
+}
@@ -1,22 +1,29 @@
 use std::fs::canonicalize;
 use std::future::Future;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
 use err_context::AnyError;
 use futures::StreamExt;
 use futures_retry::{ErrorHandler, FutureRetry, RetryPolicy};
 use log::{debug, info, warn};
+use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
 use tokio::fs::File;
 use tokio::prelude::*;
+use tokio::sync::mpsc::UnboundedSender;
 use url::Url;
 use walkdir::WalkDir;
 
+use crate::cache::FileCache;
 use crate::config::ServerSession;
 use crate::connector;
+use crate::crypto::{self, EncryptionKey, EncryptionMeta};
 use crate::utils::IterUtils;
 
 const MAX_ATTEMPTS: usize = 3;
+const ENCRYPTION_BLOCK_SIZE: usize = 4 * 1024 * 1024; // 4 MiB
 
 pub async fn register(url: &Url, username: String) -> Result<(), AnyError> {
     let pass = rpassword::prompt_password_stdout("Password: ").unwrap();
@@ -52,6 +59,7 @@ pub async fn login(
     let session = ServerSession {
         url: url.clone(),
         session_id,
+        encryption: None,
     };
 
     debug!("Saving session to {:?}: {:?}", config_file, session);
@@ -71,18 +79,43 @@ pub async fn login(
 }
 
 pub async fn upload_files(
-    session: ServerSession,
+    mut session: ServerSession,
     parallelism: usize,
+    encrypt: bool,
+    force: bool,
+    no_cache: bool,
+    archive: bool,
+    config_file: &PathBuf,
     filenames: Vec<PathBuf>,
 ) -> Result<(), AnyError> {
+    let key = if encrypt {
+        Some(Arc::new(encryption_key(&mut session, config_file).await?))
+    } else {
+        None
+    };
+
+    let cache = if no_cache {
+        None
+    } else {
+        Some(Arc::new(FileCache::open()?))
+    };
+
+    let (dirs, filenames): (Vec<PathBuf>, Vec<PathBuf>) = if archive {
+        filenames.into_iter().partition(|p| p.is_dir())
+    } else {
+        (Vec::new(), filenames)
+    };
+
+    for dir in dirs {
+        upload_archive(session.clone(), dir).await?;
+    }
+
     let filenames = unfold_dirs(filenames);
     let total_count = filenames.len();
 
-    let futures = futures::stream::iter(
-        filenames
-            .into_iter()
-            .map(move |path| upload_file(session.clone(), path)),
-    );
+    let futures = futures::stream::iter(filenames.into_iter().map(move |path| {
+        upload_file(session.clone(), key.clone(), cache.clone(), force, path)
+    }));
 
     let results = futures
         .buffer_unordered(parallelism)
@@ -107,19 +140,111 @@ pub async fn upload_files(
     }
 }
 
-async fn upload_file(session: ServerSession, path: PathBuf) -> Result<(), AnyError> {
+/// Makes sure `session` carries `EncryptionMeta` (generating and persisting a
+/// fresh salt to `config_file` on first use), prompts for the passphrase and
+/// derives the key from it. The key is derived once per invocation and shared
+/// across all files being uploaded.
+async fn encryption_key(
+    session: &mut ServerSession,
+    config_file: &PathBuf,
+) -> Result<EncryptionKey, AnyError> {
+    if session.encryption.is_none() {
+        session.encryption = Some(EncryptionMeta::generate(ENCRYPTION_BLOCK_SIZE));
+
+        if let Some(parent) = config_file.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let mut file = File::create(config_file).await?;
+        file.write_all(toml::to_string_pretty(session)?.as_bytes())
+            .await?;
+    }
+
+    let pass = rpassword::prompt_password_stdout("Encryption passphrase: ").unwrap();
+    let meta = session.encryption.as_ref().expect("just ensured above");
+
+    crypto::derive_key(&pass, meta)
+}
+
+/// Like [`encryption_key`] but for restoring rather than uploading: the caller
+/// must already have checked `session.encryption` is present (this command never
+/// generates one), so it only prompts for the passphrase and derives the key.
+fn decryption_key(session: &ServerSession) -> Result<EncryptionKey, AnyError> {
+    let pass = rpassword::prompt_password_stdout("Encryption passphrase: ").unwrap();
+    let meta = session
+        .encryption
+        .as_ref()
+        .expect("caller already checked session.encryption is Some");
+
+    crypto::derive_key(&pass, meta)
+}
+
+async fn upload_file(
+    session: ServerSession,
+    key: Option<Arc<EncryptionKey>>,
+    cache: Option<Arc<FileCache>>,
+    force: bool,
+    path: PathBuf,
+) -> Result<(), AnyError> {
     let path = canonicalize(path)?;
+
+    if !force {
+        if let Some(cache) = &cache {
+            if cache.is_unchanged(&path)? {
+                info!("File {:?} is unchanged, skipping", path);
+                return Ok(());
+            }
+        }
+    }
+
     debug!("Uploading {:?}", path);
 
-    retried(|| connector::upload_file(session.clone(), path.clone()))
+    let result = retried(|| {
+        connector::upload_file(session.clone(), key.clone(), cache.clone(), path.clone())
+    })
+    .await?;
+
+    use connector::UploadFileResponse::*;
+
+    match result {
+        Success(ref uploaded) => {
+            info!("File {:?} was uploaded", path);
+
+            if let Some(cache) = &cache {
+                let hash = uploaded
+                    .versions
+                    .last()
+                    .map(|v| v.hash.clone())
+                    .unwrap_or_default();
+
+                cache.record(&path, hash)?;
+            }
+        }
+        HashMismatch(err) => warn!("Upload of {:?} was not successful: {}", path, err),
+        BadRequest(err) => warn!("Upload of {:?} was not successful: {}", path, err),
+    }
+
+    Ok(())
+}
+
+/// Packs `root` into a single directory snapshot and uploads it as one request,
+/// instead of going through the per-file pipeline in [`upload_file`].
+async fn upload_archive(session: ServerSession, root: PathBuf) -> Result<(), AnyError> {
+    let root = canonicalize(root)?;
+    debug!("Archiving {:?}", root);
+
+    retried(|| connector::upload_archive(session.clone(), root.clone()))
         .await
         .map(|r| {
-            use connector::UploadFileResponse::*;
+            use connector::ArchiveResponse::*;
 
             match r {
-                Success(_) => info!("File {:?} was uploaded", path),
-                HashMismatch(err) => warn!("Upload of {:?} was not successful: {}", path, err),
-                BadRequest(err) => warn!("Upload of {:?} was not successful: {}", path, err),
+                Success(summary) => info!(
+                    "Archive {:?} was uploaded ({} entries)",
+                    root, summary.entry_count
+                ),
+                HashMismatch(err) => warn!("Archive upload of {:?} was not successful: {}", root, err),
+                BadRequest(err) => warn!("Archive upload of {:?} was not successful: {}", root, err),
             }
         })
 }
@@ -132,6 +257,173 @@ pub async fn list_devices(session: ServerSession) -> Result<(), AnyError> {
     Ok(())
 }
 
+pub async fn list_files(session: ServerSession, device_id: String) -> Result<(), AnyError> {
+    let files =
+        retried(move || connector::list_files(session.clone(), device_id.clone())).await?;
+
+    info!("Remote files: {:?}", files);
+
+    Ok(())
+}
+
+pub async fn download_file(
+    session: ServerSession,
+    device_id: String,
+    file_path: String,
+    version: Option<u64>,
+    decrypt: bool,
+    target_dir: PathBuf,
+) -> Result<(), AnyError> {
+    if decrypt && session.encryption.is_none() {
+        return Err(AnyError::from(
+            "This session has no encryption metadata; was the file uploaded with --encrypt?",
+        ));
+    }
+
+    tokio::fs::create_dir_all(&target_dir).await?;
+
+    let files = connector::list_files(session.clone(), device_id.clone()).await?;
+
+    let file = files
+        .into_iter()
+        .find(|f| f.original_name == file_path)
+        .ok_or_else(|| AnyError::from(format!("{:?} not found on device {:?}", file_path, device_id)))?;
+
+    let file_version = match version {
+        Some(v) => file.versions.into_iter().find(|fv| fv.version == v),
+        None => file.versions.into_iter().max_by_key(|fv| fv.version),
+    }
+    .ok_or_else(|| AnyError::from(format!("No such version of {:?}", file_path)))?;
+
+    // `original_name` is the (likely absolute) path the file was uploaded from; only
+    // its final component is safe to join onto `target_dir` — joining the rest verbatim
+    // would either discard `target_dir` (if absolute) or let `..` components escape it.
+    let file_name = Path::new(&file.original_name).file_name().ok_or_else(|| {
+        AnyError::from(format!(
+            "{:?} has no usable file name to restore to",
+            file.original_name
+        ))
+    })?;
+
+    let target = target_dir.join(file_name);
+
+    retried(|| {
+        connector::download_file(
+            session.clone(),
+            device_id.clone(),
+            file_path.clone(),
+            Some(file_version.version),
+            file_version.hash.clone(),
+            target.clone(),
+        )
+    })
+    .await?;
+
+    if decrypt {
+        let key = decryption_key(&session)?;
+        let meta = session.encryption.as_ref().expect("checked at the top of this function");
+
+        let tmp = target.with_extension("rbackup-decrypting");
+        {
+            let input = std::fs::File::open(&target)?;
+            let output = std::fs::File::create(&tmp)?;
+            crypto::open_stream(&key, meta, input, output)?;
+        }
+        tokio::fs::rename(&tmp, &target).await?;
+    }
+
+    info!(
+        "Downloaded {:?} (version {}) to {:?}",
+        file_path, file_version.version, target
+    );
+
+    Ok(())
+}
+
+/// Keeps running, uploading files under `paths` as they're created or modified.
+/// A dedicated thread owns the `notify` watcher (whose events arrive on a
+/// blocking channel) and forwards settled paths over an async channel; the main
+/// loop here drives them through the same upload pipeline as a one-shot `upload`,
+/// `parallelism` at a time, backed by the same [`FileCache`] so unchanged files
+/// are skipped.
+pub async fn watch(
+    session: ServerSession,
+    parallelism: usize,
+    paths: Vec<PathBuf>,
+) -> Result<(), AnyError> {
+    let cache = Arc::new(FileCache::open()?);
+    let uploaded = Arc::new(AtomicUsize::new(0));
+    let failed = Arc::new(AtomicUsize::new(0));
+
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+
+    std::thread::spawn(move || watch_thread(paths, tx));
+
+    info!("Watching for changes, press Ctrl+C to stop");
+
+    rx.for_each_concurrent(parallelism, move |path| {
+        let session = session.clone();
+        let cache = Arc::clone(&cache);
+        let uploaded = Arc::clone(&uploaded);
+        let failed = Arc::clone(&failed);
+
+        async move {
+            match upload_file(session, None, Some(cache), false, path.clone()).await {
+                Ok(_) => {
+                    uploaded.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    failed.fetch_add(1, Ordering::Relaxed);
+                    warn!("Could not upload {:?}: {:?}", path, e);
+                }
+            }
+
+            info!(
+                "Watch summary: {} uploaded, {} failed",
+                uploaded.load(Ordering::Relaxed),
+                failed.load(Ordering::Relaxed)
+            );
+        }
+    })
+    .await;
+
+    Ok(())
+}
+
+/// Runs the blocking `notify` watcher on its own thread, debouncing bursts of
+/// events within ~500 ms per path (so a file isn't uploaded mid-write), and
+/// forwards each settled, existing file path to `tx`.
+fn watch_thread(paths: Vec<PathBuf>, tx: UnboundedSender<PathBuf>) {
+    let (watch_tx, watch_rx) = std::sync::mpsc::channel();
+
+    let mut watcher = match watcher(watch_tx, Duration::from_millis(500)) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            warn!("Could not start filesystem watcher: {}", e);
+            return;
+        }
+    };
+
+    for path in &paths {
+        if let Err(e) = watcher.watch(path, RecursiveMode::Recursive) {
+            warn!("Could not watch {:?}: {}", path, e);
+        }
+    }
+
+    for event in watch_rx {
+        let changed_path = match event {
+            DebouncedEvent::Create(path) | DebouncedEvent::Write(path) => Some(path),
+            _ => None,
+        };
+
+        if let Some(path) = changed_path {
+            if path.is_file() && tx.send(path).is_err() {
+                break; // receiver is gone, nothing more to do
+            }
+        }
+    }
+}
+
 fn unfold_dirs(filenames: Vec<PathBuf>) -> Vec<PathBuf> {
     filenames
         .into_iter()
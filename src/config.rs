@@ -2,9 +2,13 @@ use serde::{Deserialize, Serialize};
 use url::Url;
 use uuid::Uuid;
 
+use crate::crypto::EncryptionMeta;
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ServerSession {
     #[serde(with = "url_serde")]
     pub url: Url,
     pub session_id: Uuid,
+    #[serde(default)]
+    pub encryption: Option<EncryptionMeta>,
 }
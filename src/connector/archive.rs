@@ -0,0 +1,218 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use err_context::AnyError;
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc::UnboundedSender;
+use walkdir::WalkDir;
+
+const KIND_FILE: u8 = 0;
+const KIND_DIR: u8 = 1;
+const KIND_SYMLINK: u8 = 2;
+const READ_CHUNK_SIZE: usize = 1024 * 1024; // 1 MiB
+
+/// One directory-tree entry, carrying everything needed to build its record header
+/// plus where to get its payload bytes from. Shared between [`digest`] and
+/// [`stream`] so the two passes can't drift apart and produce different bytes.
+struct Entry {
+    rel_path: PathBuf,
+    kind: u8,
+    mode: u32,
+    mtime_ms: u64,
+    data_len: u64,
+    payload: Payload,
+}
+
+enum Payload {
+    None,
+    Bytes(Vec<u8>), // symlink target, small enough to hold in full
+    File(PathBuf),  // file contents, read on demand so the caller controls buffering
+}
+
+/// Walks `root`'s directory tree without ever holding an entry's payload, a
+/// symlink target aside, in memory.
+fn walk(root: &Path) -> impl Iterator<Item = Result<Entry, AnyError>> {
+    let root = root.to_path_buf();
+
+    WalkDir::new(root.clone())
+        .follow_links(false)
+        .same_file_system(true)
+        .into_iter()
+        .filter_map(move |entry| {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(AnyError::from(e))),
+            };
+
+            let rel_path = entry
+                .path()
+                .strip_prefix(&root)
+                .unwrap_or_else(|_| entry.path())
+                .to_path_buf();
+
+            if rel_path.as_os_str().is_empty() {
+                return None; // root itself carries no useful metadata of its own
+            }
+
+            Some(build_entry(entry.path(), rel_path))
+        })
+}
+
+fn build_entry(path: &Path, rel_path: PathBuf) -> Result<Entry, AnyError> {
+    let metadata = fs::symlink_metadata(path)?;
+    let mode = metadata.permissions().mode();
+    let mtime_ms = metadata
+        .modified()?
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_millis() as u64;
+
+    let (kind, data_len, payload) = if metadata.file_type().is_symlink() {
+        let target = fs::read_link(path)?.to_string_lossy().into_owned().into_bytes();
+        (KIND_SYMLINK, target.len() as u64, Payload::Bytes(target))
+    } else if metadata.is_dir() {
+        (KIND_DIR, 0, Payload::None)
+    } else {
+        (KIND_FILE, metadata.len(), Payload::File(path.to_path_buf()))
+    };
+
+    Ok(Entry {
+        rel_path,
+        kind,
+        mode,
+        mtime_ms,
+        data_len,
+        payload,
+    })
+}
+
+fn entry_header(entry: &Entry) -> Vec<u8> {
+    let path_bytes = entry.rel_path.to_string_lossy().into_owned().into_bytes();
+
+    let mut header = Vec::with_capacity(1 + 2 + path_bytes.len() + 4 + 8 + 8);
+    header.push(entry.kind);
+    header.extend_from_slice(&(path_bytes.len() as u16).to_be_bytes());
+    header.extend_from_slice(&path_bytes);
+    header.extend_from_slice(&entry.mode.to_be_bytes());
+    header.extend_from_slice(&entry.mtime_ms.to_be_bytes());
+    header.extend_from_slice(&entry.data_len.to_be_bytes());
+
+    header
+}
+
+/// Walks `root` computing the same digest and byte count [`stream`] will produce
+/// over the wire, without ever holding more than one `READ_CHUNK_SIZE` buffer (or a
+/// symlink target) in memory — used to size and integrity-tag the request before
+/// the streamed body starts flowing, so packing never has to buffer the whole tree.
+///
+/// Walking twice (once here, once in [`stream`]) leaves a TOCTOU window: if a file
+/// under `root` changes between the two passes, the bytes `stream` sends won't
+/// match the digest computed here, and the server rejects the whole snapshot with
+/// no partial-progress path. Treating that as acceptable for now rather than
+/// reading every file into memory (or onto disk) up front just to digest it once.
+pub fn digest(root: &Path) -> Result<(String, u64, u64), AnyError> {
+    let mut hasher = Sha256::new();
+    let mut entry_count = 0u64;
+    let mut total_bytes = 0u64;
+
+    for entry in walk(root) {
+        let entry = entry?;
+        let header = entry_header(&entry);
+
+        Digest::input(&mut hasher, &header);
+        total_bytes += header.len() as u64;
+
+        match &entry.payload {
+            Payload::None => {}
+            Payload::Bytes(data) => {
+                Digest::input(&mut hasher, data);
+                total_bytes += data.len() as u64;
+            }
+            Payload::File(path) => {
+                let mut file = File::open(path)?;
+                let mut buf = vec![0u8; READ_CHUNK_SIZE];
+
+                loop {
+                    let n = file.read(&mut buf)?;
+                    if n == 0 {
+                        break;
+                    }
+                    Digest::input(&mut hasher, &buf[..n]);
+                    total_bytes += n as u64;
+                }
+            }
+        }
+
+        entry_count += 1;
+    }
+
+    Ok((hex::encode(hasher.result()), entry_count, total_bytes))
+}
+
+/// Runs on a dedicated thread (mirroring [`crate::commands::watch_thread`]'s bridge
+/// from blocking I/O to an async body): walks `root` again and sends each entry's
+/// header and payload, in `READ_CHUNK_SIZE` pieces, over `tx` as they're read from
+/// disk. `tx` is unbounded, so reading is not held back by the receiver: pieces
+/// that have been read but not yet consumed stay queued in memory.
+pub fn stream(root: PathBuf, tx: UnboundedSender<Result<Vec<u8>, AnyError>>) {
+    for entry in walk(&root) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                let _ = tx.send(Err(e));
+                return;
+            }
+        };
+
+        if tx.send(Ok(entry_header(&entry))).is_err() {
+            return; // receiver is gone, nothing more to do
+        }
+
+        let sent = match &entry.payload {
+            Payload::None => Ok(()),
+            Payload::Bytes(data) => send_bytes(&tx, data),
+            Payload::File(path) => send_file(&tx, path),
+        };
+
+        if sent.is_err() {
+            return;
+        }
+    }
+}
+
+fn send_bytes(tx: &UnboundedSender<Result<Vec<u8>, AnyError>>, data: &[u8]) -> Result<(), ()> {
+    for chunk in data.chunks(READ_CHUNK_SIZE) {
+        tx.send(Ok(chunk.to_vec())).map_err(|_| ())?;
+    }
+
+    Ok(())
+}
+
+fn send_file(tx: &UnboundedSender<Result<Vec<u8>, AnyError>>, path: &Path) -> Result<(), ()> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            let _ = tx.send(Err(AnyError::from(e)));
+            return Err(());
+        }
+    };
+    let mut buf = vec![0u8; READ_CHUNK_SIZE];
+
+    loop {
+        let n = match file.read(&mut buf) {
+            Ok(n) => n,
+            Err(e) => {
+                let _ = tx.send(Err(AnyError::from(e)));
+                return Err(());
+            }
+        };
+
+        if n == 0 {
+            return Ok(());
+        }
+
+        tx.send(Ok(buf[..n].to_vec())).map_err(|_| ())?;
+    }
+}
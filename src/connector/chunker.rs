@@ -0,0 +1,114 @@
+use std::io::{BufReader, Read};
+
+use err_context::AnyError;
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+
+const WINDOW_SIZE: usize = 64;
+const MIN_CHUNK_SIZE: usize = 1024 * 1024; // 1 MiB
+const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024; // 16 MiB
+const AVG_CHUNK_SIZE: u64 = 4 * 1024 * 1024; // 4 MiB
+const MASK: u64 = AVG_CHUNK_SIZE.next_power_of_two() - 1;
+
+static BUZHASH_TABLE: Lazy<[u64; 256]> = Lazy::new(|| {
+    let mut table = [0u64; 256];
+    let mut state = 0x2545_f491_4f6c_dd1d_u64; // fixed seed, so chunking is deterministic
+
+    for slot in table.iter_mut() {
+        // splitmix64
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        *slot = z ^ (z >> 31);
+    }
+
+    table
+});
+
+pub struct Chunk {
+    pub digest: String,
+    pub data: Vec<u8>,
+}
+
+/// A rolling buzhash over a sliding `WINDOW_SIZE`-byte window. Because the window
+/// is exactly 64 bytes wide and the hash is 64 bits, a byte that leaves the window
+/// has accumulated exactly one full rotation (mod 64) since it entered, so it can be
+/// removed by xor-ing in its un-rotated table value.
+struct RollingHash {
+    window: [u8; WINDOW_SIZE],
+    pos: usize,
+    filled: usize,
+    hash: u64,
+}
+
+impl RollingHash {
+    fn new() -> Self {
+        RollingHash {
+            window: [0; WINDOW_SIZE],
+            pos: 0,
+            filled: 0,
+            hash: 0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.hash = self.hash.rotate_left(1) ^ BUZHASH_TABLE[byte as usize];
+
+        if self.filled == WINDOW_SIZE {
+            let outgoing = self.window[self.pos];
+            self.hash ^= BUZHASH_TABLE[outgoing as usize];
+        } else {
+            self.filled += 1;
+        }
+
+        self.window[self.pos] = byte;
+        self.pos = (self.pos + 1) % WINDOW_SIZE;
+    }
+}
+
+/// Splits the bytes read from `reader` into content-defined chunks: a chunk boundary
+/// is cut whenever the rolling hash of the last `WINDOW_SIZE` bytes satisfies
+/// `hash & MASK == MASK`, which yields an average chunk size around `AVG_CHUNK_SIZE`.
+/// `MIN_CHUNK_SIZE` and `MAX_CHUNK_SIZE` bound pathological inputs (e.g.
+/// already-incompressible or repetitive data) so chunk counts stay predictable
+/// either way. Reads one byte at a time through a `BufReader` (so the underlying
+/// reads are still bulk). Every chunk keeps its data, so the returned chunks hold
+/// the whole input in memory.
+pub fn chunk<R: Read>(reader: R) -> Result<Vec<Chunk>, AnyError> {
+    let mut reader = BufReader::new(reader);
+    let mut chunks = Vec::new();
+    let mut current = Vec::new();
+    let mut window = RollingHash::new();
+    let mut hasher = Sha256::new();
+    let mut byte = [0u8; 1];
+
+    loop {
+        if reader.read(&mut byte)? == 0 {
+            break;
+        }
+
+        window.push(byte[0]);
+        current.push(byte[0]);
+        Digest::input(&mut hasher, &byte);
+
+        let at_boundary = current.len() >= MIN_CHUNK_SIZE && window.hash & MASK == MASK;
+
+        if at_boundary || current.len() >= MAX_CHUNK_SIZE {
+            chunks.push(Chunk {
+                digest: hex::encode(Digest::result_reset(&mut hasher)),
+                data: std::mem::take(&mut current),
+            });
+            window = RollingHash::new();
+        }
+    }
+
+    if !current.is_empty() || chunks.is_empty() {
+        chunks.push(Chunk {
+            digest: hex::encode(Digest::result(hasher)),
+            data: current,
+        });
+    }
+
+    Ok(chunks)
+}
@@ -0,0 +1,50 @@
+use std::path::Path;
+
+use err_context::AnyError;
+use futures::StreamExt;
+use log::debug;
+use reqwest::Response;
+use sha2::{Digest, Sha256};
+use tokio::fs::File;
+use tokio::prelude::*;
+
+/// Streams `resp`'s body to `target` on disk, hashing it as bytes arrive rather
+/// than buffering the whole response in memory. If the final digest doesn't match
+/// `expected_hash`, the partially-written file is removed and an error is
+/// returned, so a restore never silently leaves corrupted data on disk.
+pub async fn stream_to_file(
+    resp: Response,
+    target: &Path,
+    expected_hash: &str,
+) -> Result<(), AnyError> {
+    let mut file = File::create(target).await?;
+    let mut hasher = Sha256::new();
+    let mut stream = resp.bytes_stream();
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        Digest::input(&mut hasher, &chunk);
+        file.write_all(&chunk).await?;
+    }
+
+    file.flush().await?;
+    drop(file);
+
+    let digest = hex::encode(hasher.result());
+
+    if digest != expected_hash {
+        debug!(
+            "Hash mismatch for {:?}: expected {}, got {}",
+            target, expected_hash, digest
+        );
+
+        tokio::fs::remove_file(target).await?;
+
+        return Err(AnyError::from(format!(
+            "Downloaded file {:?} failed hash verification (expected {}, got {})",
+            target, expected_hash, digest
+        )));
+    }
+
+    Ok(())
+}
@@ -1,29 +1,41 @@
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use err_context::AnyError;
 use log::debug;
 use once_cell::sync::Lazy;
 use reqwest::header::{HeaderMap, HeaderValue};
-use reqwest::{Client, Response, StatusCode};
+use reqwest::{Body, Client, Response, StatusCode};
 use serde::Serialize;
 use url::Url;
 use uuid::Uuid;
 
+use crate::cache::{FileCache, PendingUpload};
 use crate::config::ServerSession;
-use crate::connector::structs::{DevicesListResponse, LoginResponse};
+use crate::connector::chunker::Chunk;
+use crate::connector::structs::{
+    ChunkManifest, CommittedChunksResponse, DevicesListResponse, KnownChunksResponse,
+    ListFilesResponse, LoginResponse,
+};
 use crate::connector::upload::UploadedFile;
+use crate::crypto::EncryptionKey;
 use crate::errors::HttpError::InvalidStatus;
 
+mod archive;
+mod chunker;
+mod download;
 mod structs;
 mod upload;
 
-pub use crate::connector::structs::UploadFileResponse;
+pub use crate::connector::structs::{
+    ArchiveResponse, UploadFileResponse, UploadedFile as RemoteFile,
+};
 
 static CLIENT: Lazy<Client> = Lazy::new(reqwest::Client::new);
 
 const SESSION_HEADER: &str = "RBackup-Session-Pass";
-// const FILE_HASH_HEADER: &str = "RBackup-File-Hash";
+const FILE_HASH_HEADER: &str = "RBackup-File-Hash";
 
 mod paths {
     pub mod account {
@@ -33,9 +45,15 @@ mod paths {
 
     pub mod list {
         pub const DEVICES: &str = "list/devices";
+        pub const FILES: &str = "list/files";
     }
 
     pub const UPLOAD: &str = "upload";
+    pub const KNOWN_CHUNKS: &str = "upload/known-chunks";
+    pub const UPLOAD_CHUNK: &str = "upload/chunk";
+    pub const RESUME: &str = "upload/resume";
+    pub const UPLOAD_ARCHIVE: &str = "upload/archive";
+    pub const DOWNLOAD: &str = "download";
 }
 
 pub async fn register(url: &Url, username: String, password: String) -> Result<(), AnyError> {
@@ -85,19 +103,154 @@ pub async fn login(
     }
 }
 
+/// Uploads `file` chunk by chunk so that a retry (whether from [`crate::commands`]'s
+/// `retried` wrapper or a fresh invocation after the process was killed) only has
+/// to send the chunks that weren't already committed, rather than starting over.
+/// Progress is tracked under `upload_id`, a fresh id unless `cache` already has a
+/// `PendingUpload` for this path, and persisted to `cache` as each chunk succeeds.
 pub async fn upload_file(
     session: ServerSession,
+    key: Option<Arc<EncryptionKey>>,
+    cache: Option<Arc<FileCache>>,
     file: PathBuf,
+) -> Result<UploadFileResponse, AnyError> {
+    let uploaded = UploadedFile::open(file.clone())?;
+    let chunks = match &key {
+        Some(key) => {
+            let meta = session.encryption.as_ref().ok_or_else(|| {
+                AnyError::from("Session has no encryption metadata for an encrypted upload")
+            })?;
+
+            uploaded.encrypted_chunks(key, meta)?
+        }
+        None => uploaded.chunks()?,
+    };
+    let manifest = UploadedFile::manifest(&chunks);
+    let file_hash = UploadedFile::file_hash(&chunks);
+
+    let pending = match &cache {
+        Some(cache) => cache.pending_upload(&file)?,
+        None => None,
+    };
+
+    let upload_id = pending
+        .as_ref()
+        .map(|p| p.upload_id)
+        .unwrap_or_else(Uuid::new_v4);
+
+    let resuming = pending.is_some();
+    let mut committed = pending.map(|p| p.committed).unwrap_or_default();
+
+    if resuming {
+        match resume_upload(session.clone(), upload_id).await {
+            Ok(server_committed) => {
+                for digest in server_committed {
+                    if !committed.contains(&digest) {
+                        committed.push(digest);
+                    }
+                }
+            }
+            Err(e) => debug!("Could not fetch resume state for {:?}: {:?}", upload_id, e),
+        }
+    }
+
+    let known = known_chunks(session.clone(), &manifest).await?.0;
+
+    for (chunk, is_known) in chunks.iter().zip(known.iter()) {
+        if *is_known || committed.contains(&chunk.digest) {
+            continue;
+        }
+
+        upload_chunk(session.clone(), upload_id, chunk).await?;
+
+        committed.push(chunk.digest.clone());
+
+        if let Some(cache) = &cache {
+            cache.save_pending_upload(
+                &file,
+                &PendingUpload {
+                    upload_id,
+                    committed: committed.clone(),
+                },
+            )?;
+        }
+    }
+
+    let response = finalize_upload(session, upload_id, &uploaded, &manifest, &file_hash).await?;
+
+    if let Some(cache) = &cache {
+        cache.clear_pending_upload(&file)?;
+    }
+
+    Ok(response)
+}
+
+async fn upload_chunk(session: ServerSession, upload_id: Uuid, chunk: &Chunk) -> Result<(), AnyError> {
+    let url = create_url(&session.url, paths::UPLOAD_CHUNK)?;
+
+    let resp = CLIENT
+        .put(url)
+        .query(&[
+            ("upload_id", upload_id.to_string()),
+            ("digest", chunk.digest.clone()),
+        ])
+        .headers(session.into())
+        .body(chunk.data.clone())
+        .send()
+        .await?;
+
+    debug!("Received response: {:?}", resp);
+
+    match resp.status() {
+        StatusCode::OK => Ok(()),
+        status => Err(Box::from(InvalidStatus {
+            expected: 200,
+            found: status.as_u16(),
+        })),
+    }
+}
+
+/// Asks the server which chunk digests it has already committed for `upload_id`,
+/// so a resumed upload knows where to pick back up.
+pub async fn resume_upload(session: ServerSession, upload_id: Uuid) -> Result<Vec<String>, AnyError> {
+    let response = get_authenticated(
+        session,
+        paths::RESUME,
+        &[("upload_id", upload_id.to_string())],
+    )
+    .await?;
+
+    match response.status() {
+        StatusCode::OK => response
+            .json::<CommittedChunksResponse>()
+            .await
+            .map(|r| r.0)
+            .map_err(AnyError::from),
+        status => Err(Box::from(InvalidStatus {
+            expected: 200,
+            found: status.as_u16(),
+        })),
+    }
+}
+
+async fn finalize_upload(
+    session: ServerSession,
+    upload_id: Uuid,
+    file: &UploadedFile,
+    manifest: &ChunkManifest,
+    file_hash: &str,
 ) -> Result<UploadFileResponse, AnyError> {
     let url = create_url(&session.url, paths::UPLOAD)?;
 
-    let file = UploadedFile::open(file)?;
+    let mut query = file.as_query()?;
+    query.push(("upload_id".to_string(), upload_id.to_string()));
 
     let resp = CLIENT
         .put(url)
-        .query(&file.as_query()?)
+        .query(&query)
+        .header(FILE_HASH_HEADER, file_hash)
         .headers(session.into())
-        .multipart(file.into_multipart_form().await?)
+        .json(manifest)
         .send()
         .await?;
 
@@ -114,6 +267,83 @@ pub async fn upload_file(
     }
 }
 
+/// Packs `root`'s directory tree into one snapshot (see [`archive`]) and streams it
+/// as a single request instead of one request per file, which cuts per-file HTTP
+/// overhead for trees dominated by small files. The tree is walked twice — once to
+/// compute the digest and size needed to build the request, once more (on a
+/// dedicated thread, mirroring [`crate::commands::watch_thread`]) to stream the
+/// actual bytes into the request body — so no pre-built snapshot of the tree is
+/// assembled up front. The channel between the two is unbounded, so when disk reads
+/// outpace the upload the pieces read so far queue up in memory.
+pub async fn upload_archive(
+    session: ServerSession,
+    root: PathBuf,
+) -> Result<ArchiveResponse, AnyError> {
+    let url = create_url(&session.url, paths::UPLOAD_ARCHIVE)?;
+
+    let (hash, entry_count, size) = archive::digest(&root)?;
+
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    let stream_root = root.clone();
+    std::thread::spawn(move || archive::stream(stream_root, tx));
+
+    let resp = CLIENT
+        .put(url)
+        .query(&[
+            ("root_path", root.to_string_lossy().to_string()),
+            ("entry_count", entry_count.to_string()),
+            ("size", size.to_string()),
+        ])
+        .header(FILE_HASH_HEADER, hash)
+        .headers(session.into())
+        .body(Body::wrap_stream(rx))
+        .send()
+        .await?;
+
+    debug!("Received response: {:?}", resp);
+
+    match resp.status() {
+        StatusCode::OK => Ok(ArchiveResponse::Success(resp.json().await?)),
+        StatusCode::PRECONDITION_FAILED => {
+            Ok(ArchiveResponse::HashMismatch(resp.text().await?))
+        }
+        StatusCode::BAD_REQUEST => Ok(ArchiveResponse::BadRequest(resp.text().await?)),
+        status => Err(Box::from(InvalidStatus {
+            expected: 200,
+            found: status.as_u16(),
+        })),
+    }
+}
+
+/// Asks the server which of `manifest`'s chunk digests it already has stored, so
+/// `upload_file` only has to transfer the ones that are actually missing.
+pub async fn known_chunks(
+    session: ServerSession,
+    manifest: &ChunkManifest,
+) -> Result<KnownChunksResponse, AnyError> {
+    let url = create_url(&session.url, paths::KNOWN_CHUNKS)?;
+
+    let resp = CLIENT
+        .get(url)
+        .headers(session.into())
+        .json(manifest)
+        .send()
+        .await?;
+
+    debug!("Received response: {:?}", resp);
+
+    match resp.status() {
+        StatusCode::OK => resp
+            .json::<KnownChunksResponse>()
+            .await
+            .map_err(AnyError::from),
+        status => Err(Box::from(InvalidStatus {
+            expected: 200,
+            found: status.as_u16(),
+        })),
+    }
+}
+
 // fn get_header(resp: &Response, name: &str) -> Option<String> {
 //     let x: Option<&HeaderValue> = resp.headers().get(name);
 //
@@ -135,6 +365,60 @@ pub async fn list_devices(session: ServerSession) -> Result<DevicesListResponse,
     }
 }
 
+pub async fn list_files(
+    session: ServerSession,
+    device_id: String,
+) -> Result<Vec<RemoteFile>, AnyError> {
+    let response =
+        get_authenticated(session, paths::list::FILES, &[("device_id", device_id)]).await?;
+
+    match response.status() {
+        StatusCode::OK => response
+            .json::<ListFilesResponse>()
+            .await
+            .map(|r| r.0)
+            .map_err(AnyError::from),
+        status => Err(Box::from(InvalidStatus {
+            expected: 200,
+            found: status.as_u16(),
+        })),
+    }
+}
+
+pub async fn download_file(
+    session: ServerSession,
+    device_id: String,
+    file_path: String,
+    version: Option<u64>,
+    expected_hash: String,
+    target: PathBuf,
+) -> Result<(), AnyError> {
+    let url = create_url(&session.url, paths::DOWNLOAD)?;
+
+    let mut query = vec![("device_id", device_id), ("file_path", file_path)];
+
+    if let Some(version) = version {
+        query.push(("version", version.to_string()));
+    }
+
+    let resp = CLIENT
+        .get(url)
+        .query(&query)
+        .headers(session.into())
+        .send()
+        .await?;
+
+    debug!("Received response: {:?}", resp);
+
+    match resp.status() {
+        StatusCode::OK => download::stream_to_file(resp, &target, &expected_hash).await,
+        status => Err(Box::from(InvalidStatus {
+            expected: 200,
+            found: status.as_u16(),
+        })),
+    }
+}
+
 async fn get_authenticated<Q: Serialize + ?Sized>(
     session: ServerSession,
     path: &str,
@@ -1,5 +1,5 @@
 use chrono::NaiveDateTime;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 #[derive(Deserialize, Debug)]
@@ -10,6 +10,9 @@ pub struct LoginResponse {
 #[derive(Deserialize, Debug)]
 pub struct DevicesListResponse(pub Vec<String>);
 
+#[derive(Deserialize, Debug)]
+pub struct ListFilesResponse(pub Vec<UploadedFile>);
+
 #[derive(Deserialize, Debug)]
 pub enum UploadFileResponse {
     Success(UploadedFile),
@@ -17,7 +20,7 @@ pub enum UploadFileResponse {
     BadRequest(String),
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug)]
 pub struct UploadedFile {
     pub id: u64,
     pub device_id: String,
@@ -25,7 +28,7 @@ pub struct UploadedFile {
     pub versions: Vec<FileVersion>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug)]
 pub struct FileVersion {
     pub version: u64,
     pub size: u64,
@@ -34,3 +37,37 @@ pub struct FileVersion {
     pub mtime: NaiveDateTime,
     pub storage_name: String,
 }
+
+/// The ordered sequence of chunk digests (and their sizes) a chunked upload is
+/// made of, sent to `upload/known-chunks` and alongside the upload itself so the
+/// server can reassemble the file from the chunks it already has plus the ones
+/// freshly transferred.
+#[derive(Serialize, Debug)]
+pub struct ChunkManifest {
+    pub digests: Vec<String>,
+    pub sizes: Vec<u64>,
+}
+
+/// One entry per digest in the `ChunkManifest` that was queried, `true` if the
+/// server already has that chunk stored.
+#[derive(Deserialize, Debug)]
+pub struct KnownChunksResponse(pub Vec<bool>);
+
+/// The chunk digests the server has already committed for a given (in-progress)
+/// `upload_id`, returned by `upload/resume` so a retried upload knows which
+/// chunks it can skip re-sending.
+#[derive(Deserialize, Debug)]
+pub struct CommittedChunksResponse(pub Vec<String>);
+
+#[derive(Deserialize, Debug)]
+pub enum ArchiveResponse {
+    Success(ArchiveSummary),
+    HashMismatch(String),
+    BadRequest(String),
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ArchiveSummary {
+    pub id: u64,
+    pub entry_count: u64,
+}
@@ -1,13 +1,14 @@
 use err_context::AnyError;
-use log::{debug, error};
-use reqwest::multipart::{Form, Part};
-use reqwest::Body;
-use sha2::{Digest, Sha256};
 use std::fs::Metadata;
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 
+use sha2::{Digest, Sha256};
+
+use crate::connector::chunker::{self, Chunk};
+use crate::connector::structs::ChunkManifest;
+use crate::crypto::{self, EncryptionKey, EncryptionMeta};
+
 pub struct UploadedFile {
     path: PathBuf,
     metadata: Metadata,
@@ -41,76 +42,108 @@ impl UploadedFile {
         ])
     }
 
-    pub async fn into_multipart_form(self) -> Result<Form, AnyError> {
-        let hasher = Arc::new(Mutex::new(sha2::Sha256::new()));
-
-        let body: Part = self.get_body(Arc::clone(&hasher)).await?;
-        let hash = self.get_hash_part(hasher)?;
+    /// Splits the file into content-defined chunks in a single pass through the
+    /// chunker. The returned chunks carry their data, so the whole file is held in
+    /// memory until the upload is done.
+    pub fn chunks(&self) -> Result<Vec<Chunk>, AnyError> {
+        let file = std::fs::File::open(&self.path)?;
 
-        Ok(Form::new().part("file", body).part("file-hash", hash))
+        chunker::chunk(file)
     }
 
-    async fn get_body(&self, hasher: Arc<Mutex<Sha256>>) -> Result<Part, AnyError> {
-        use tokio::fs::File as TokioFile;
-        use tokio::io::AsyncReadExt;
-
-        let stream =
-            futures::stream::unfold(TokioFile::open(&self.path).await?, move |mut file| {
-                let hasher = Arc::clone(&hasher);
-
-                async move {
-                    let mut buff = Vec::with_capacity(4096);
-
-                    if file.read(&mut buff).await.unwrap() > 0 {
-                        let hasher = &mut *hasher.lock().expect("Poisoned mutex!");
-                        debug!("Streaming chunk of {} bytes of", buff.len());
-                        sha2::digest::Input::input(hasher, &buff);
-                        Some((Ok::<_, AnyError>(buff), file))
-                    } else {
-                        None
-                    }
-                }
-            });
-
-        Ok(Part::stream(Body::wrap_stream(stream)))
+    /// Seals the whole file into `meta`-sized AES-GCM blocks (via
+    /// [`crypto::seal_stream`]) and then splits the resulting ciphertext into
+    /// content-defined chunks, so the digests (and therefore the dedup index and
+    /// the `file-hash` check) are taken over ciphertext rather than plaintext.
+    ///
+    /// Sealing has to happen over the whole file before chunking it, not the other
+    /// way around: [`crypto::open_stream`] tells frames apart purely by length, so
+    /// only the very last frame of the whole file may be short. Sealing each CDC
+    /// chunk on its own (chunking first) would put a short frame at every chunk
+    /// boundary and break that assumption on restore.
+    pub fn encrypted_chunks(
+        &self,
+        key: &EncryptionKey,
+        meta: &EncryptionMeta,
+    ) -> Result<Vec<Chunk>, AnyError> {
+        let file = std::fs::File::open(&self.path)?;
+        let ciphertext = crypto::seal_stream(key, meta, file)?;
+
+        chunker::chunk(std::io::Cursor::new(ciphertext))
     }
 
-    fn get_hash_part(&self, hasher: Arc<Mutex<Sha256>>) -> Result<Part, AnyError> {
-        use HashingPartState::*;
-
-        let stream = futures::stream::unfold(Init(hasher), move |state| async move {
-            match state {
-                Init(arc) => match UploadedFile::unwrap_hasher(arc) {
-                    Ok(hasher) => Some((Ok::<_, AnyError>(String::new()), Hashing(hasher))),
-                    Err(e) => {
-                        error!("Could not unwrap hasher: {:?}", e);
-                        Some((Err(e), Closed)) // Closed here won't be ever read, because we return Err
-                    }
-                },
-                Hashing(hasher) => {
-                    let hash = hex::encode(hasher.result());
-                    debug!("Calculated hash {}", hash);
-                    Some((Ok::<_, AnyError>(hash), Closed))
-                }
-                Closed => None,
-            }
-        });
-
-        Ok(Part::stream(Body::wrap_stream(stream)))
+    pub fn manifest(chunks: &[Chunk]) -> ChunkManifest {
+        ChunkManifest {
+            digests: chunks.iter().map(|c| c.digest.clone()).collect(),
+            sizes: chunks.iter().map(|c| c.data.len() as u64).collect(),
+        }
     }
 
-    fn unwrap_hasher(arc: Arc<Mutex<Sha256>>) -> Result<Sha256, AnyError> {
-        Arc::try_unwrap(arc)
-            .map_err(|_| AnyError::from("Could not unwrap Arc!"))
-            .and_then(|m| {
-                m.into_inner()
-                    .map_err(|_| AnyError::from("Could not unwrap Mutex!"))
-            })
+    /// SHA-256 over the concatenation of all chunks in upload order — i.e. over
+    /// ciphertext when the upload is encrypted — sent as the `file-hash` integrity
+    /// header so the server can verify the reassembled file end to end.
+    pub fn file_hash(chunks: &[Chunk]) -> String {
+        let mut hasher = Sha256::new();
+
+        for chunk in chunks {
+            Digest::input(&mut hasher, &chunk.data);
+        }
+
+        hex::encode(hasher.result())
     }
 }
 
-enum HashingPartState {
-    Init(Arc<Mutex<Sha256>>),
-    Hashing(Sha256),
-    Closed,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Regression test: sealing each content-defined chunk independently used to
+    /// put a short AES-GCM frame at every chunk boundary, which `open_stream`
+    /// (which tells frames apart purely by length) silently misread once
+    /// decrypting past the file's first chunk. This round-trips a file big enough
+    /// to be split into more than one content-defined chunk through
+    /// encrypt -> chunk -> reassemble -> decrypt and checks the bytes come back
+    /// unchanged.
+    #[test]
+    fn encrypted_upload_round_trips_across_multiple_chunks() {
+        let path = std::env::temp_dir().join(format!("rbackup-test-{}", std::process::id()));
+
+        // Larger than the chunker's 16 MiB maximum chunk size, so at least one cut is forced.
+        let plaintext = pseudo_random_bytes(20 * 1024 * 1024);
+        std::fs::write(&path, &plaintext).unwrap();
+
+        let meta = EncryptionMeta::generate(256 * 1024);
+        let key = crypto::derive_key("correct horse battery staple", &meta).unwrap();
+
+        let file = UploadedFile::open(path.clone()).unwrap();
+        let chunks = file.encrypted_chunks(&key, &meta).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(
+            chunks.len() >= 2,
+            "test fixture should span more than one content-defined chunk"
+        );
+
+        let ciphertext: Vec<u8> = chunks.iter().flat_map(|c| c.data.clone()).collect();
+
+        let mut decrypted = Vec::new();
+        crypto::open_stream(&key, &meta, std::io::Cursor::new(ciphertext), &mut decrypted).unwrap();
+
+        assert_eq!(decrypted, plaintext);
+    }
+
+    fn pseudo_random_bytes(len: usize) -> Vec<u8> {
+        let mut state = 0x9e3779b97f4a7c15_u64;
+        let mut out = Vec::with_capacity(len);
+
+        while out.len() < len {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            out.extend_from_slice(&state.to_le_bytes());
+        }
+
+        out.truncate(len);
+        out
+    }
 }
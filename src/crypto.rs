@@ -0,0 +1,171 @@
+use std::io::{Read, Write};
+
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use argon2::Argon2;
+use err_context::AnyError;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+pub const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const CIPHER_ID_AES_256_GCM: &str = "aes-256-gcm";
+
+/// KDF salt and cipher parameters an upload was encrypted with, persisted alongside
+/// the session so a later `Download` can re-derive the same key from the passphrase
+/// and undo the framing applied here.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EncryptionMeta {
+    pub salt: String, // hex-encoded
+    pub cipher: String,
+    pub block_size: usize,
+}
+
+impl EncryptionMeta {
+    pub fn generate(block_size: usize) -> Self {
+        let mut salt = vec![0u8; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+
+        EncryptionMeta {
+            salt: hex::encode(salt),
+            cipher: CIPHER_ID_AES_256_GCM.to_string(),
+            block_size,
+        }
+    }
+}
+
+pub struct EncryptionKey([u8; 32]);
+
+/// Derives a 256-bit key from `passphrase` using Argon2id, memory-hard so a stolen
+/// salt doesn't make offline brute-forcing cheap.
+pub fn derive_key(passphrase: &str, meta: &EncryptionMeta) -> Result<EncryptionKey, AnyError> {
+    let salt = hex::decode(&meta.salt)?;
+    let mut key = [0u8; 32];
+
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+        .map_err(|e| AnyError::from(format!("Could not derive encryption key: {}", e)))?;
+
+    Ok(EncryptionKey(key))
+}
+
+/// Seals one block with AES-256-GCM under a fresh random nonce, framing the result
+/// as `nonce || ciphertext || tag` so each block can be decrypted independently.
+pub fn seal_block(key: &EncryptionKey, plaintext: &[u8]) -> Result<Vec<u8>, AnyError> {
+    let cipher = Aes256Gcm::new(Key::from_slice(&key.0));
+
+    let mut nonce_bytes = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce_bytes);
+    let nonce = Nonce::from_slice(&nonce_bytes);
+
+    let ciphertext = cipher
+        .encrypt(nonce, plaintext)
+        .map_err(|e| AnyError::from(format!("Could not encrypt block: {}", e)))?;
+
+    let mut framed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+    framed.extend_from_slice(&nonce_bytes);
+    framed.extend_from_slice(&ciphertext);
+
+    Ok(framed)
+}
+
+/// Reverses `seal_block`, splitting the nonce back off the front of the frame.
+pub fn open_block(key: &EncryptionKey, framed: &[u8]) -> Result<Vec<u8>, AnyError> {
+    if framed.len() < NONCE_LEN {
+        return Err(AnyError::from("Encrypted block is shorter than a nonce"));
+    }
+
+    let (nonce_bytes, ciphertext) = framed.split_at(NONCE_LEN);
+    let cipher = Aes256Gcm::new(Key::from_slice(&key.0));
+    let nonce = Nonce::from_slice(nonce_bytes);
+
+    cipher
+        .decrypt(nonce, ciphertext)
+        .map_err(|e| AnyError::from(format!("Could not decrypt block: {}", e)))
+}
+
+/// Seals everything read from `reader` as one continuous sequence of
+/// `meta.block_size`-sized frames (only the very last frame, at end of stream, can
+/// be shorter), each produced by [`seal_block`] under its own random nonce and
+/// concatenated back to back.
+///
+/// This has to run over the whole file's plaintext stream in one go, not per
+/// content-defined chunk: sealing each CDC chunk independently would make every
+/// chunk boundary also end a (short) frame, and [`open_stream`] — which tells
+/// frames apart purely by length — would then misparse anything past the first
+/// chunk. Content-defined chunking for dedup happens afterwards, over the
+/// resulting ciphertext.
+///
+/// Note this also means two uploads of the same plaintext file never produce the
+/// same ciphertext (each frame gets a fresh random nonce), so unlike plaintext
+/// uploads, an unchanged encrypted file won't dedup against a previous encrypted
+/// upload of it — only within the same upload.
+pub fn seal_stream<R: Read>(key: &EncryptionKey, meta: &EncryptionMeta, mut reader: R) -> Result<Vec<u8>, AnyError> {
+    let block_size = meta.block_size.max(1);
+    let mut framed = Vec::new();
+    let mut buf = vec![0u8; block_size];
+
+    loop {
+        let n = read_block(&mut reader, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+
+        framed.extend(seal_block(key, &buf[..n])?);
+
+        if n < block_size {
+            break; // short read means EOF
+        }
+    }
+
+    Ok(framed)
+}
+
+/// Reverses [`seal_stream`], decrypting one frame at a time and writing its
+/// plaintext to `writer` as soon as it's available, so at most one frame (not the
+/// whole file) is ever held in memory. Frames are told apart purely by size: every
+/// frame but the last is exactly `NONCE_LEN + meta.block_size + TAG_LEN` bytes (a
+/// full block), so no length needs to be stored alongside the ciphertext to parse
+/// them back out.
+pub fn open_stream<R: Read, W: Write>(
+    key: &EncryptionKey,
+    meta: &EncryptionMeta,
+    mut reader: R,
+    mut writer: W,
+) -> Result<(), AnyError> {
+    let full_frame_len = NONCE_LEN + meta.block_size.max(1) + TAG_LEN;
+    let mut buf = vec![0u8; full_frame_len];
+
+    loop {
+        let n = read_block(&mut reader, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+
+        writer.write_all(&open_block(key, &buf[..n])?)?;
+
+        if n < full_frame_len {
+            break; // short read means EOF
+        }
+    }
+
+    Ok(())
+}
+
+/// Fills `buf` from `reader` as far as it will go before hitting EOF, unlike
+/// `Read::read` (which may stop short of `buf.len()` even mid-stream). Returns the
+/// number of bytes actually read, which is less than `buf.len()` only at EOF.
+fn read_block<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize, AnyError> {
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        let n = reader.read(&mut buf[filled..])?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+
+    Ok(filled)
+}
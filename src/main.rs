@@ -8,9 +8,11 @@ use url::Url;
 use crate::config::ServerSession;
 use crate::Command::*;
 
+mod cache;
 mod commands;
 mod config;
 mod connector;
+mod crypto;
 mod errors;
 mod utils;
 
@@ -35,13 +37,38 @@ enum Command {
         username: String,
     },
     ListDevices,
+    ListFiles {
+        device_id: String,
+    },
+    Download {
+        device_id: String,
+        file_path: String,
+        #[structopt(long)]
+        version: Option<u64>,
+        #[structopt(long)]
+        decrypt: bool,
+        target_dir: PathBuf,
+    },
     Upload {
         #[structopt(short, long)]
         recursive: bool,
         #[structopt(short, long, default_value = "4")]
         parallelism: usize,
+        #[structopt(long)]
+        encrypt: bool,
+        #[structopt(long)]
+        force: bool,
+        #[structopt(long)]
+        no_cache: bool,
+        #[structopt(long)]
+        archive: bool,
         filenames: Vec<PathBuf>,
     },
+    Watch {
+        #[structopt(short, long, default_value = "4")]
+        parallelism: usize,
+        paths: Vec<PathBuf>,
+    },
 }
 
 #[tokio::main]
@@ -68,9 +95,28 @@ async fn main() -> Result<(), AnyError> {
             let session = load_session(&config_file).await?;
             commands::list_devices(session).await
         }
+        ListFiles { device_id } => {
+            let session = load_session(&config_file).await?;
+            commands::list_files(session, device_id).await
+        }
+        Download {
+            device_id,
+            file_path,
+            version,
+            decrypt,
+            target_dir,
+        } => {
+            let session = load_session(&config_file).await?;
+            commands::download_file(session, device_id, file_path, version, decrypt, target_dir)
+                .await
+        }
         Upload {
             recursive,
             parallelism,
+            encrypt,
+            force,
+            no_cache,
+            archive,
             filenames,
         } => {
             if filenames.is_empty() {
@@ -78,7 +124,7 @@ async fn main() -> Result<(), AnyError> {
             }
 
             for path in filenames.iter() {
-                if path.is_dir() && !recursive {
+                if path.is_dir() && !recursive && !archive {
                     return Err(AnyError::from(format!(
                         "{:?} is a dir but you didn't enable dirs recursion!",
                         path
@@ -87,12 +133,33 @@ async fn main() -> Result<(), AnyError> {
             }
 
             debug!(
-                "Upload: recursive: {}, parallelism: {}, filenames: {:?}",
-                recursive, parallelism, filenames
+                "Upload: recursive: {}, parallelism: {}, encrypt: {}, force: {}, no_cache: {}, archive: {}, filenames: {:?}",
+                recursive, parallelism, encrypt, force, no_cache, archive, filenames
             );
 
             let session = load_session(&config_file).await?;
-            commands::upload_files(session, parallelism, filenames).await
+            commands::upload_files(
+                session,
+                parallelism,
+                encrypt,
+                force,
+                no_cache,
+                archive,
+                &config_file,
+                filenames,
+            )
+            .await
+        }
+        Watch {
+            parallelism,
+            paths,
+        } => {
+            if paths.is_empty() {
+                return Err(AnyError::from("You must provide at least one path!"));
+            }
+
+            let session = load_session(&config_file).await?;
+            commands::watch(session, parallelism, paths).await
         }
     }
 }